@@ -1,7 +1,8 @@
 
 use std::net::IpAddr;
-use std::io::{Result, ErrorKind};
+use std::io::{Result, Error, ErrorKind, IoSliceMut};
 use std::mem;
+use std::time::Duration;
 
 use libc as c;
 
@@ -9,11 +10,69 @@ use compat::{IntoInner, FromInner, AsInner, cvt, setsockopt, getsockopt};
 
 // Following constants are not defined in libc (as for 0.2.17 version)
 const IPPROTO_ICMP: c::c_int = 1;
+const IPPROTO_ICMPV6: c::c_int = 58;
 // Ipv4
 const IP_TOS: c::c_int = 1;
+const IP_RECVTTL: c::c_int = 12;
+const IP_RECVTOS: c::c_int = 13;
 // Ipv6
 const IPV6_UNICAST_HOPS: c::c_int = 16;
 const IPV6_TCLASS: c::c_int = 67;
+const IPV6_RECVHOPLIMIT: c::c_int = 51;
+const IPV6_RECVTCLASS: c::c_int = 66;
+const IPV6_HOPLIMIT: c::c_int = 52;
+
+/// Per-packet ancillary data recovered alongside a `recv_from_with_meta` read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecvMeta {
+    pub ttl: Option<u8>,
+    pub tos: Option<u8>,
+}
+
+// `CMSG_FIRSTHDR`/`CMSG_NXTHDR`/`CMSG_DATA` assume `cmsghdr`-aligned storage
+// (glibc aligns control messages to `size_t`); a plain `[u8; N]` on the stack
+// carries no such guarantee, so wrap it in a type aligned to the same width.
+#[repr(align(8))]
+struct CmsgBuf([u8; 128]);
+
+impl Default for CmsgBuf {
+    fn default() -> CmsgBuf {
+        CmsgBuf([0u8; 128])
+    }
+}
+
+fn parse_recv_meta(msg: &c::msghdr) -> RecvMeta {
+    let mut meta = RecvMeta::default();
+
+    unsafe {
+        let mut cmsg = c::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let hdr = &*cmsg;
+            match (hdr.cmsg_level, hdr.cmsg_type) {
+                (c::IPPROTO_IP, t) if t == c::IP_TTL => {
+                    let v = *(c::CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.ttl = Some(v as u8);
+                }
+                (c::IPPROTO_IP, t) if t == IP_TOS => {
+                    let v = *(c::CMSG_DATA(cmsg) as *const u8);
+                    meta.tos = Some(v);
+                }
+                (c::IPPROTO_IPV6, t) if t == IPV6_HOPLIMIT => {
+                    let v = *(c::CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.ttl = Some(v as u8);
+                }
+                (c::IPPROTO_IPV6, t) if t == IPV6_TCLASS => {
+                    let v = *(c::CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.tos = Some(v as u8);
+                }
+                _ => {}
+            }
+            cmsg = c::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+
+    meta
+}
 
 #[cfg(target_os = "linux")]
 use libc::SOCK_CLOEXEC;
@@ -34,9 +93,13 @@ impl Socket {
             IpAddr::V4(..) => c::AF_INET,
             IpAddr::V6(..) => c::AF_INET6,
         };
+        let proto = match addr {
+            IpAddr::V4(..) => IPPROTO_ICMP,
+            IpAddr::V6(..) => IPPROTO_ICMPV6,
+        };
 
         let fd = unsafe {
-            cvt(c::socket(family, c::SOCK_RAW | SOCK_CLOEXEC, IPPROTO_ICMP))?
+            cvt(c::socket(family, c::SOCK_RAW | SOCK_CLOEXEC, proto))?
         };
 
         Ok(Socket {
@@ -46,6 +109,32 @@ impl Socket {
         })
     }
 
+    pub fn bind(addr: IpAddr) -> Result<Socket> {
+        let family = match addr {
+            IpAddr::V4(..) => c::AF_INET,
+            IpAddr::V6(..) => c::AF_INET6,
+        };
+        let proto = match addr {
+            IpAddr::V4(..) => IPPROTO_ICMP,
+            IpAddr::V6(..) => IPPROTO_ICMPV6,
+        };
+
+        let fd = unsafe {
+            cvt(c::socket(family, c::SOCK_RAW | SOCK_CLOEXEC, proto))?
+        };
+
+        let local = addr.into_inner();
+        unsafe {
+            cvt(c::bind(fd, &local, mem::size_of_val(&local) as c::socklen_t))?;
+        }
+
+        Ok(Socket {
+            fd: fd,
+            family: family,
+            peer: unsafe { mem::zeroed() },
+        })
+    }
+
     pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
         let ret = unsafe {
             cvt(c::recv(
@@ -100,6 +189,23 @@ impl Socket {
         Ok(ret as usize)
     }
 
+    pub fn send_to(&self, buf: &[u8], dest: IpAddr) -> Result<usize> {
+        let dest = dest.into_inner();
+        let ret = unsafe {
+            cvt(c::sendto(
+                    self.fd,
+                    buf.as_ptr() as *mut c::c_void,
+                    buf.len() as c::size_t,
+                    0,
+                    &dest,
+                    mem::size_of_val(&dest) as c::socklen_t,
+                )
+            )?
+        };
+
+        Ok(ret as usize)
+    }
+
     pub fn set_ttl(&self, ttl: u32) -> Result<()> {
         match self.family {
             c::AF_INET => setsockopt(self, c::IPPROTO_IP, c::IP_TTL, ttl as c::c_int),
@@ -141,6 +247,145 @@ impl Socket {
         }
     }
 
+    pub fn recv_from_with_meta(&self, bufs: &mut [IoSliceMut]) -> Result<(usize, IpAddr, RecvMeta)> {
+        match self.family {
+            c::AF_INET => {
+                setsockopt(self, c::IPPROTO_IP, IP_RECVTTL, 1 as c::c_int)?;
+                setsockopt(self, c::IPPROTO_IP, IP_RECVTOS, 1 as c::c_int)?;
+            }
+            c::AF_INET6 => {
+                setsockopt(self, c::IPPROTO_IPV6, IPV6_RECVHOPLIMIT, 1 as c::c_int)?;
+                setsockopt(self, c::IPPROTO_IPV6, IPV6_RECVTCLASS, 1 as c::c_int)?;
+            }
+            _ => unreachable!(),
+        }
+
+        let mut peer: c::sockaddr = unsafe { mem::uninitialized() };
+        let mut ctrl = CmsgBuf::default();
+
+        let mut msg: c::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut peer as *mut _ as *mut c::c_void;
+        msg.msg_namelen = mem::size_of_val(&peer) as c::socklen_t;
+        msg.msg_iov = bufs.as_mut_ptr() as *mut c::iovec;
+        msg.msg_iovlen = bufs.len() as _;
+        msg.msg_control = ctrl.0.as_mut_ptr() as *mut c::c_void;
+        msg.msg_controllen = ctrl.0.len() as _;
+
+        let ret = unsafe { cvt(c::recvmsg(self.fd, &mut msg, 0)) };
+
+        match ret {
+            Ok(n) => Ok((n as usize, IpAddr::from_inner(peer), parse_recv_meta(&msg))),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => {
+                Ok((0, IpAddr::from_inner(peer), RecvMeta::default()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let mut one = nonblocking as c::c_int;
+        unsafe {
+            cvt(c::ioctl(self.fd, c::FIONBIO, &mut one))?;
+        }
+        Ok(())
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        setsockopt(self, c::SOL_SOCKET, c::SO_RCVBUF, size as c::c_int)
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_RCVBUF)?;
+        Ok(raw as usize)
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        setsockopt(self, c::SOL_SOCKET, c::SO_SNDBUF, size as c::c_int)
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_SNDBUF)?;
+        Ok(raw as usize)
+    }
+
+    pub fn take_error(&self) -> Result<Option<Error>> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_ERROR)?;
+        if raw == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Error::from_raw_os_error(raw as i32)))
+        }
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.set_timeout(dur, c::SO_RCVTIMEO)
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.timeout(c::SO_RCVTIMEO)
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.set_timeout(dur, c::SO_SNDTIMEO)
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.timeout(c::SO_SNDTIMEO)
+    }
+
+    fn set_timeout(&self, dur: Option<Duration>, kind: c::c_int) -> Result<()> {
+        let timeout = match dur {
+            Some(dur) => {
+                if dur.as_secs() == 0 && dur.subsec_nanos() == 0 {
+                    return Err(Error::new(ErrorKind::InvalidInput,
+                                           "cannot set a 0 duration timeout"));
+                }
+
+                let secs = if dur.as_secs() > c::time_t::max_value() as u64 {
+                    c::time_t::max_value()
+                } else {
+                    dur.as_secs() as c::time_t
+                };
+                let mut timeout = c::timeval {
+                    tv_sec: secs,
+                    tv_usec: (dur.subsec_nanos() / 1000) as c::suseconds_t,
+                };
+                if timeout.tv_sec == 0 && timeout.tv_usec == 0 {
+                    // Round a sub-microsecond duration up rather than letting
+                    // it collapse into the "no timeout" sentinel below.
+                    timeout.tv_usec = 1;
+                }
+                timeout
+            }
+            None => c::timeval { tv_sec: 0, tv_usec: 0 },
+        };
+
+        setsockopt(self, c::SOL_SOCKET, kind, timeout)
+    }
+
+    fn timeout(&self, kind: c::c_int) -> Result<Option<Duration>> {
+        let raw: c::timeval = getsockopt(self, c::SOL_SOCKET, kind)?;
+        if raw.tv_sec == 0 && raw.tv_usec == 0 {
+            Ok(None)
+        } else {
+            let sec = raw.tv_sec as u64;
+            let nsec = (raw.tv_usec as u32) * 1000;
+            Ok(Some(Duration::new(sec, nsec)))
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Socket> {
+        let fd = unsafe {
+            cvt(c::fcntl(self.fd, c::F_DUPFD_CLOEXEC, 0))?
+        };
+
+        Ok(Socket {
+            fd: fd,
+            family: self.family,
+            peer: self.peer,
+        })
+    }
+
 }
 
 impl Drop for Socket {
@@ -155,4 +400,40 @@ impl AsInner<c::c_int> for Socket {
     fn as_inner(&self) -> &c::c_int {
         &self.fd
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_recv_meta_reads_ipv4_ttl_and_tos_cmsgs() {
+        let mut ctrl = CmsgBuf::default();
+        let mut msg: c::msghdr = unsafe { mem::zeroed() };
+        msg.msg_control = ctrl.0.as_mut_ptr() as *mut c::c_void;
+        msg.msg_controllen = ctrl.0.len() as _;
+
+        unsafe {
+            let ttl_hdr = c::CMSG_FIRSTHDR(&msg);
+            (*ttl_hdr).cmsg_level = c::IPPROTO_IP;
+            (*ttl_hdr).cmsg_type = c::IP_TTL;
+            (*ttl_hdr).cmsg_len = c::CMSG_LEN(mem::size_of::<c::c_int>() as u32) as _;
+            *(c::CMSG_DATA(ttl_hdr) as *mut c::c_int) = 37;
+
+            let tos_hdr = c::CMSG_NXTHDR(&msg, ttl_hdr);
+            assert!(!tos_hdr.is_null());
+            (*tos_hdr).cmsg_level = c::IPPROTO_IP;
+            (*tos_hdr).cmsg_type = IP_TOS;
+            (*tos_hdr).cmsg_len = c::CMSG_LEN(mem::size_of::<u8>() as u32) as _;
+            *(c::CMSG_DATA(tos_hdr) as *mut u8) = 7;
+
+            let used = (tos_hdr as usize - ctrl.0.as_ptr() as usize)
+                + c::CMSG_SPACE(mem::size_of::<u8>() as u32) as usize;
+            msg.msg_controllen = used as _;
+        }
+
+        let meta = parse_recv_meta(&msg);
+        assert_eq!(meta.ttl, Some(37));
+        assert_eq!(meta.tos, Some(7));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,474 @@
+
+use std::net::IpAddr;
+use std::io::{Result, Error, ErrorKind, IoSliceMut};
+use std::mem;
+use std::sync::Once;
+use std::time::Duration;
+
+use winapi as c;
+use kernel32 as k32;
+use ws2_32 as ws;
+
+use compat::{IntoInner, FromInner, AsInner, cvt, setsockopt, getsockopt};
+
+// Winsock has no notion of a raw ICMP "protocol family" constant in `winapi`
+// itself, so these mirror the values WinSock exposes via <ws2tcpip.h>.
+const IPPROTO_ICMP: c::c_int = 1;
+const IPPROTO_ICMPV6: c::c_int = 58;
+// Ipv4
+const IP_TOS: c::c_int = 3;
+const IP_RECVTTL: c::c_int = 12;
+const IP_RECVTOS: c::c_int = 13;
+// Ipv6
+const IPV6_UNICAST_HOPS: c::c_int = 4;
+const IPV6_TCLASS: c::c_int = 39;
+const IPV6_HOPLIMIT: c::c_int = 21;
+const IPV6_RECVHOPLIMIT: c::c_int = 21;
+
+/// Per-packet ancillary data recovered alongside a `recv_from_with_meta` read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecvMeta {
+    pub ttl: Option<u8>,
+    pub tos: Option<u8>,
+}
+
+/// Length type accepted by `send`/`recv` on Winsock, as opposed to the
+/// `size_t` used by the POSIX equivalents.
+pub type wrlen_t = c::c_int;
+
+static WSA_INIT: Once = Once::new();
+
+fn init() {
+    WSA_INIT.call_once(|| unsafe {
+        let mut data: c::WSADATA = mem::zeroed();
+        let ret = ws::WSAStartup(0x202, &mut data);
+        assert_eq!(ret, 0);
+    });
+}
+
+// `SOCKET` is pointer-width, so it must be compared against
+// `INVALID_SOCKET` directly rather than narrowed through `c_int` the way
+// `cvt` checks a POSIX fd against -1.
+fn cvt_socket(socket: c::SOCKET) -> Result<c::SOCKET> {
+    if socket == c::INVALID_SOCKET {
+        Err(Error::last_os_error())
+    } else {
+        Ok(socket)
+    }
+}
+
+// `WSARecvMsg` isn't linked like the rest of Winsock; it has to be fetched
+// per-socket through `WSAIoctl`'s `SIO_GET_EXTENSION_FUNCTION_POINTER`.
+fn wsarecvmsg_ptr(fd: c::SOCKET) -> Result<c::LPFN_WSARECVMSG> {
+    let guid = c::WSAID_WSARECVMSG;
+    let mut func: c::LPFN_WSARECVMSG = unsafe { mem::zeroed() };
+    let mut bytes: c::DWORD = 0;
+
+    unsafe {
+        cvt(ws::WSAIoctl(
+                fd,
+                c::SIO_GET_EXTENSION_FUNCTION_POINTER,
+                &guid as *const _ as *mut c::c_void,
+                mem::size_of_val(&guid) as c::DWORD,
+                &mut func as *mut _ as *mut c::c_void,
+                mem::size_of_val(&func) as c::DWORD,
+                &mut bytes,
+                0 as *mut _,
+                None,
+            )
+        )?;
+    }
+
+    Ok(func)
+}
+
+fn parse_recv_meta(msg: &c::WSAMSG) -> RecvMeta {
+    let mut meta = RecvMeta::default();
+
+    unsafe {
+        let mut cmsg = c::WSA_CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let hdr = &*cmsg;
+            match (hdr.cmsg_level, hdr.cmsg_type) {
+                (c::IPPROTO_IP, t) if t == c::IP_TTL => {
+                    let v = *(c::WSA_CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.ttl = Some(v as u8);
+                }
+                (c::IPPROTO_IP, t) if t == IP_TOS => {
+                    let v = *(c::WSA_CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.tos = Some(v as u8);
+                }
+                (c::IPPROTO_IPV6, t) if t == IPV6_HOPLIMIT => {
+                    let v = *(c::WSA_CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.ttl = Some(v as u8);
+                }
+                (c::IPPROTO_IPV6, t) if t == IPV6_TCLASS => {
+                    let v = *(c::WSA_CMSG_DATA(cmsg) as *const c::c_int);
+                    meta.tos = Some(v as u8);
+                }
+                _ => {}
+            }
+            cmsg = c::WSA_CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+
+    meta
+}
+
+pub struct Socket {
+    fd: c::SOCKET,
+    family: c::c_int,
+    peer: c::SOCKADDR,
+}
+
+impl Socket {
+
+    pub fn connect(addr: IpAddr) -> Result<Socket> {
+        init();
+
+        let family = match addr {
+            IpAddr::V4(..) => c::AF_INET,
+            IpAddr::V6(..) => c::AF_INET6,
+        };
+        let proto = match addr {
+            IpAddr::V4(..) => IPPROTO_ICMP,
+            IpAddr::V6(..) => IPPROTO_ICMPV6,
+        };
+
+        let fd = unsafe {
+            cvt_socket(ws::WSASocketW(
+                    family,
+                    c::SOCK_RAW,
+                    proto,
+                    0 as *mut _,
+                    0,
+                    c::WSA_FLAG_OVERLAPPED,
+                )
+            )?
+        };
+
+        Ok(Socket {
+            fd: fd,
+            family: family,
+            peer: addr.into_inner(),
+        })
+    }
+
+    pub fn bind(addr: IpAddr) -> Result<Socket> {
+        init();
+
+        let family = match addr {
+            IpAddr::V4(..) => c::AF_INET,
+            IpAddr::V6(..) => c::AF_INET6,
+        };
+        let proto = match addr {
+            IpAddr::V4(..) => IPPROTO_ICMP,
+            IpAddr::V6(..) => IPPROTO_ICMPV6,
+        };
+
+        let fd = unsafe {
+            cvt_socket(ws::WSASocketW(
+                    family,
+                    c::SOCK_RAW,
+                    proto,
+                    0 as *mut _,
+                    0,
+                    c::WSA_FLAG_OVERLAPPED,
+                )
+            )?
+        };
+
+        let local = addr.into_inner();
+        unsafe {
+            cvt(ws::bind(fd, &local, mem::size_of_val(&local) as c::c_int))?;
+        }
+
+        Ok(Socket {
+            fd: fd,
+            family: family,
+            peer: unsafe { mem::zeroed() },
+        })
+    }
+
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize> {
+        let ret = unsafe {
+            cvt(ws::recv(
+                    self.fd,
+                    buf.as_mut_ptr() as *mut c::c_char,
+                    buf.len() as wrlen_t,
+                    0,
+            ))
+        };
+
+        match ret {
+            Ok(size) => Ok(size as usize),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpAddr)> {
+        let mut peer: c::SOCKADDR = unsafe { mem::uninitialized() };
+        let ret = unsafe {
+            cvt(ws::recvfrom(
+                    self.fd,
+                    buf.as_mut_ptr() as *mut c::c_char,
+                    buf.len() as wrlen_t,
+                    0,
+                    &mut peer,
+                    &mut (mem::size_of_val(&peer) as c::c_int)
+                )
+            )
+        };
+
+        match ret {
+            Ok(size) => Ok((size as usize, IpAddr::from_inner(peer))),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => Ok((0, IpAddr::from_inner(peer))),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize> {
+        let ret = unsafe {
+            cvt(ws::sendto(
+                    self.fd,
+                    buf.as_ptr() as *const c::c_char,
+                    buf.len() as wrlen_t,
+                    0,
+                    &self.peer,
+                    mem::size_of_val(&self.peer) as c::c_int,
+                )
+            )?
+        };
+
+        Ok(ret as usize)
+    }
+
+    pub fn send_to(&self, buf: &[u8], dest: IpAddr) -> Result<usize> {
+        let dest = dest.into_inner();
+        let ret = unsafe {
+            cvt(ws::sendto(
+                    self.fd,
+                    buf.as_ptr() as *const c::c_char,
+                    buf.len() as wrlen_t,
+                    0,
+                    &dest,
+                    mem::size_of_val(&dest) as c::c_int,
+                )
+            )?
+        };
+
+        Ok(ret as usize)
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> Result<()> {
+        match self.family {
+            c::AF_INET => setsockopt(self, c::IPPROTO_IP, c::IP_TTL, ttl as c::c_int),
+            c::AF_INET6 => setsockopt(self, c::IPPROTO_IPV6, IPV6_UNICAST_HOPS, ttl as c::c_int),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn ttl(&self) -> Result<u32> {
+        match self.family {
+            c::AF_INET => getsockopt(self, c::IPPROTO_IP, c::IP_TTL),
+            c::AF_INET6 => getsockopt(self, c::IPPROTO_IPV6, IPV6_UNICAST_HOPS),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_broadcast(&self, broadcast: bool) -> Result<()> {
+        setsockopt(&self, c::SOL_SOCKET, c::SO_BROADCAST, broadcast as c::c_int)
+    }
+
+    pub fn broadcast(&self) -> Result<bool> {
+        let raw: c::c_int = getsockopt(&self, c::SOL_SOCKET, c::SO_BROADCAST)?;
+        Ok(raw != 0)
+    }
+
+    pub fn set_qos(&self, qos: u8) -> Result<()> {
+        match self.family {
+            c::AF_INET => setsockopt(&self, c::IPPROTO_IP, IP_TOS, qos as c::c_int),
+            c::AF_INET6 => setsockopt(&self, c::IPPROTO_IPV6, IPV6_TCLASS, qos as c::c_int),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn qos(&self) -> Result<u8> {
+        match self.family {
+            c::AF_INET => getsockopt(&self, c::IPPROTO_IP, IP_TOS),
+            c::AF_INET6 => getsockopt(&self, c::IPPROTO_IPV6, IPV6_TCLASS),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<()> {
+        setsockopt(self, c::SOL_SOCKET, c::SO_RCVBUF, size as c::c_int)
+    }
+
+    pub fn recv_buffer_size(&self) -> Result<usize> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_RCVBUF)?;
+        Ok(raw as usize)
+    }
+
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<()> {
+        setsockopt(self, c::SOL_SOCKET, c::SO_SNDBUF, size as c::c_int)
+    }
+
+    pub fn send_buffer_size(&self) -> Result<usize> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_SNDBUF)?;
+        Ok(raw as usize)
+    }
+
+    pub fn take_error(&self) -> Result<Option<Error>> {
+        let raw: c::c_int = getsockopt(self, c::SOL_SOCKET, c::SO_ERROR)?;
+        if raw == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Error::from_raw_os_error(raw as i32)))
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let mut one = nonblocking as c::u_long;
+        unsafe {
+            cvt(ws::ioctlsocket(self.fd, c::FIONBIO, &mut one))?;
+        }
+        Ok(())
+    }
+
+    pub fn recv_from_with_meta(&self, bufs: &mut [IoSliceMut]) -> Result<(usize, IpAddr, RecvMeta)> {
+        match self.family {
+            c::AF_INET => {
+                setsockopt(self, c::IPPROTO_IP, IP_RECVTTL, 1 as c::c_int)?;
+                setsockopt(self, c::IPPROTO_IP, IP_RECVTOS, 1 as c::c_int)?;
+            }
+            c::AF_INET6 => {
+                setsockopt(self, c::IPPROTO_IPV6, IPV6_RECVHOPLIMIT, 1 as c::c_int)?;
+                setsockopt(self, c::IPPROTO_IPV6, IPV6_TCLASS, 1 as c::c_int)?;
+            }
+            _ => unreachable!(),
+        }
+
+        let wsarecvmsg = wsarecvmsg_ptr(self.fd)?;
+
+        let mut peer: c::SOCKADDR = unsafe { mem::uninitialized() };
+        let mut ctrl = [0u8; 128];
+        let mut wsabufs: Vec<c::WSABUF> = bufs.iter_mut().map(|buf| {
+            c::WSABUF {
+                len: buf.len() as c::u_long,
+                buf: buf.as_mut_ptr() as *mut c::CHAR,
+            }
+        }).collect();
+
+        let mut msg: c::WSAMSG = unsafe { mem::zeroed() };
+        msg.name = &mut peer as *mut _ as *mut c::SOCKADDR;
+        msg.namelen = mem::size_of_val(&peer) as c::INT;
+        msg.lpBuffers = wsabufs.as_mut_ptr();
+        msg.dwBufferCount = wsabufs.len() as c::DWORD;
+        msg.Control.buf = ctrl.as_mut_ptr() as *mut c::CHAR;
+        msg.Control.len = ctrl.len() as c::u_long;
+
+        let mut n: c::DWORD = 0;
+        unsafe {
+            cvt(wsarecvmsg(self.fd, &mut msg, &mut n, 0 as *mut _, None))?;
+        }
+
+        Ok((n as usize, IpAddr::from_inner(peer), parse_recv_meta(&msg)))
+    }
+
+    pub fn set_read_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.set_timeout(dur, c::SO_RCVTIMEO)
+    }
+
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.timeout(c::SO_RCVTIMEO)
+    }
+
+    pub fn set_write_timeout(&self, dur: Option<Duration>) -> Result<()> {
+        self.set_timeout(dur, c::SO_SNDTIMEO)
+    }
+
+    pub fn write_timeout(&self) -> Result<Option<Duration>> {
+        self.timeout(c::SO_SNDTIMEO)
+    }
+
+    // Unlike the `timeval` that Unix's SO_RCVTIMEO/SO_SNDTIMEO take, Winsock
+    // wants a plain millisecond `DWORD`.
+    fn set_timeout(&self, dur: Option<Duration>, kind: c::c_int) -> Result<()> {
+        let timeout = match dur {
+            Some(dur) => {
+                if dur.as_secs() == 0 && dur.subsec_nanos() == 0 {
+                    return Err(Error::new(ErrorKind::InvalidInput,
+                                           "cannot set a 0 duration timeout"));
+                }
+
+                let mut ms = dur.as_secs().saturating_mul(1000)
+                    .saturating_add((dur.subsec_nanos() / 1_000_000) as u64);
+                if ms == 0 {
+                    // Round a sub-millisecond duration up rather than letting
+                    // it collapse into the "no timeout" sentinel below.
+                    ms = 1;
+                }
+
+                if ms > c::DWORD::max_value() as u64 {
+                    c::DWORD::max_value()
+                } else {
+                    ms as c::DWORD
+                }
+            }
+            None => 0,
+        };
+
+        setsockopt(self, c::SOL_SOCKET, kind, timeout)
+    }
+
+    fn timeout(&self, kind: c::c_int) -> Result<Option<Duration>> {
+        let raw: c::DWORD = getsockopt(self, c::SOL_SOCKET, kind)?;
+        if raw == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Duration::from_millis(raw as u64)))
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Socket> {
+        let mut info: c::WSAPROTOCOL_INFOW = unsafe { mem::zeroed() };
+        unsafe {
+            cvt(ws::WSADuplicateSocketW(self.fd, k32::GetCurrentProcessId(), &mut info))?;
+        }
+
+        let fd = unsafe {
+            cvt_socket(ws::WSASocketW(
+                    info.iAddressFamily,
+                    info.iSocketType,
+                    info.iProtocol,
+                    &mut info,
+                    0,
+                    c::WSA_FLAG_OVERLAPPED,
+                )
+            )?
+        };
+
+        Ok(Socket {
+            fd: fd,
+            family: self.family,
+            peer: self.peer,
+        })
+    }
+
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let _ = unsafe {
+            ws::closesocket(self.fd)
+        };
+    }
+}
+
+impl AsInner<c::SOCKET> for Socket {
+    fn as_inner(&self) -> &c::SOCKET {
+        &self.fd
+    }
+}